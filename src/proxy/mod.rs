@@ -0,0 +1,45 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use pingora_core::server::Server;
+use pingora_error::Result;
+
+use crate::config::{self, Identifiable};
+
+pub mod discovery;
+pub mod readiness;
+pub mod service;
+pub mod upstream;
+
+/// Shared helper for reloading a `DashMap`-backed resource registry (e.g.
+/// `upstream::UPSTREAM_MAP`) from a freshly loaded configuration.
+pub trait MapOperations<T: Identifiable> {
+    fn reload_resources(&self, resources: Vec<Arc<T>>);
+}
+
+impl<T: Identifiable> MapOperations<T> for DashMap<String, Arc<T>> {
+    fn reload_resources(&self, resources: Vec<Arc<T>>) {
+        self.clear();
+        for resource in resources {
+            self.insert(resource.id().to_string(), resource);
+        }
+    }
+}
+
+/// Loads upstreams and registers their health check background services,
+/// plus the liveness/readiness probe server, with the Pingora `Server`.
+///
+/// This replaces each upstream spinning up its own runtime for health
+/// checks (see `upstream::load_static_upstreams`): the services it returns
+/// are started on the server's own shared runtime instead.
+pub fn register_health_services(server: &mut Server, config: &config::Config) -> Result<()> {
+    for health_check_service in upstream::load_static_upstreams(config)? {
+        server.add_service(health_check_service);
+    }
+
+    if let Some(addr) = &config.pingora.readiness_addr {
+        server.add_service(readiness::readiness_service(addr));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use http::StatusCode;
+use pingora_core::apps::http_app::ServeHttp;
+use pingora_core::protocols::http::ServerSession;
+use pingora_core::services::listening::Service as ListeningService;
+use serde::Serialize;
+
+use super::upstream::UPSTREAM_MAP;
+
+/// Per-upstream healthy/total backend counts reported by `/ready`.
+#[derive(Serialize)]
+struct UpstreamHealth {
+    healthy: usize,
+    total: usize,
+}
+
+/// Serves `/live` and `/ready` probes derived from `UPSTREAM_MAP`.
+///
+/// Bound to its own port (separate from the proxy and admin listeners) so it
+/// can be firewalled independently, e.g. for Kubernetes liveness/readiness
+/// probes.
+pub struct ReadinessApp;
+
+#[async_trait]
+impl ServeHttp for ReadinessApp {
+    async fn response(&self, http_stream: &mut ServerSession) -> http::Response<Vec<u8>> {
+        match http_stream.req_header().uri.path() {
+            "/live" => json_response(StatusCode::OK, &HashMap::<(), ()>::new()),
+            "/ready" => {
+                let mut report: HashMap<String, UpstreamHealth> = HashMap::new();
+                let mut all_healthy = true;
+
+                for entry in UPSTREAM_MAP.iter() {
+                    let (healthy, total) = entry.value().healthy_backend_count();
+                    all_healthy &= healthy > 0;
+                    report.insert(entry.key().clone(), UpstreamHealth { healthy, total });
+                }
+
+                let status = if all_healthy {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                json_response(status, &report)
+            }
+            _ => http::Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Vec::new())
+                .expect("building a 404 response should never fail"),
+        }
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> http::Response<Vec<u8>> {
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    http::Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .expect("building a readiness response should never fail")
+}
+
+/// Builds the liveness/readiness `Service`, bound to `addr`.
+///
+/// Register the returned service with the `Server` via `server.add_service`
+/// alongside the proxy and upstream health check services.
+pub fn readiness_service(addr: &str) -> ListeningService<ReadinessApp> {
+    let mut service = ListeningService::new("readiness".to_string(), ReadinessApp);
+    service.add_tcp(addr);
+    service
+}
@@ -0,0 +1,160 @@
+use async_trait::async_trait;
+use http::StatusCode;
+use pingora_core::upstreams::peer::HttpPeer;
+use pingora_error::{Error, ErrorType, Result};
+use pingora_load_balancing::Backend;
+use pingora_proxy::{ProxyHttp, Session};
+
+use super::upstream::{upstream_fetch, ProxyUpstream};
+use std::sync::Arc;
+
+/// Per-request state threaded through the `ProxyHttp` lifecycle.
+///
+/// `upstream_id` is expected to already be set (by request routing, before
+/// `upstream_peer` runs) to the id of the `ProxyUpstream` that should serve
+/// this request. `upstream`/`backend` are filled in by `upstream_peer` once
+/// a backend has been selected, so the outcome hooks below can feed the
+/// result back into passive health checking.
+#[derive(Default)]
+pub struct ProxyCtx {
+    pub upstream_id: Option<String>,
+    upstream: Option<Arc<ProxyUpstream>>,
+    backend: Option<Backend>,
+    /// Whether the outcome for this request has already been reported.
+    /// `logging` runs once per request regardless of how it failed, so
+    /// without this a connect failure reported in `fail_to_connect` would
+    /// also get reported a second time by `logging`.
+    outcome_reported: bool,
+}
+
+/// The proxy's `ProxyHttp` implementation.
+///
+/// Besides picking the upstream peer, it feeds real proxied outcomes back
+/// into each upstream's passive health checking (see
+/// `ProxyUpstream::report_result`), complementing the active checks that
+/// run as background services.
+pub struct McpProxyService;
+
+#[async_trait]
+impl ProxyHttp for McpProxyService {
+    type CTX = ProxyCtx;
+
+    fn new_ctx(&self) -> Self::CTX {
+        ProxyCtx::default()
+    }
+
+    async fn upstream_peer(
+        &self,
+        session: &mut Session,
+        ctx: &mut Self::CTX,
+    ) -> Result<Box<HttpPeer>> {
+        let upstream_id = ctx.upstream_id.as_deref().ok_or_else(|| {
+            Error::explain(ErrorType::InternalError, "no upstream matched for request")
+        })?;
+        let upstream = upstream_fetch(upstream_id)
+            .ok_or_else(|| Error::explain(ErrorType::InternalError, "upstream not found"))?;
+        let backend = upstream.select_backend(session).ok_or_else(|| {
+            Error::explain(ErrorType::InternalError, "no healthy backend available")
+        })?;
+        let peer = backend
+            .ext
+            .get::<HttpPeer>()
+            .cloned()
+            .ok_or_else(|| Error::explain(ErrorType::InternalError, "backend missing HttpPeer"))?;
+
+        ctx.backend = Some(backend);
+        ctx.upstream = Some(upstream);
+
+        Ok(Box::new(peer))
+    }
+
+    fn fail_to_connect(
+        &self,
+        _session: &mut Session,
+        _peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        e: Box<Error>,
+    ) -> Box<Error> {
+        report_outcome(ctx, false);
+        e
+    }
+
+    async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX) {
+        let status = session.response_written().map(|resp| resp.status);
+        let success = !request_failed(e, status);
+        report_outcome(ctx, success);
+    }
+}
+
+/// Whether a proxied request should count as a failure for passive health
+/// checking: any error the request ended with (a connect failure has
+/// already been reported by `fail_to_connect`) or a 5xx response from the
+/// backend.
+///
+/// Ordinary 4xx client-error responses (bad request, not found, ...) are
+/// *not* a failure here — they reflect the request, not the backend's
+/// health, and counting them would eject a perfectly healthy backend.
+fn request_failed(e: Option<&Error>, status: Option<StatusCode>) -> bool {
+    e.is_some() || status.is_some_and(|status| status.is_server_error())
+}
+
+/// Feeds the outcome of a proxied request back into the selected upstream's
+/// passive health checking, if a backend was actually selected for it.
+///
+/// Reports at most once per request: `fail_to_connect` and `logging` can
+/// both run for the same failed request, but the outcome must only be
+/// counted once against the backend's consecutive-failure counter.
+fn report_outcome(ctx: &mut ProxyCtx, success: bool) {
+    if ctx.outcome_reported {
+        return;
+    }
+    ctx.outcome_reported = true;
+
+    if let (Some(upstream), Some(backend)) = (&ctx.upstream, &ctx.backend) {
+        upstream.report_result(backend, success);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_errors_do_not_count_as_failures() {
+        assert!(!request_failed(None, Some(StatusCode::BAD_REQUEST)));
+        assert!(!request_failed(None, Some(StatusCode::NOT_FOUND)));
+    }
+
+    #[test]
+    fn server_errors_count_as_failures() {
+        assert!(request_failed(None, Some(StatusCode::INTERNAL_SERVER_ERROR)));
+        assert!(request_failed(None, Some(StatusCode::SERVICE_UNAVAILABLE)));
+    }
+
+    #[test]
+    fn successful_responses_are_not_failures() {
+        assert!(!request_failed(None, Some(StatusCode::OK)));
+    }
+
+    #[test]
+    fn connect_errors_count_as_failures_even_without_a_response() {
+        let e = Error::new_str("connect refused");
+        assert!(request_failed(Some(e.as_ref()), None));
+    }
+
+    #[test]
+    fn report_outcome_only_fires_once_per_request() {
+        let mut ctx = ProxyCtx::default();
+        assert!(!ctx.outcome_reported);
+
+        // Simulates fail_to_connect reporting the failure...
+        report_outcome(&mut ctx, false);
+        assert!(ctx.outcome_reported);
+
+        // ...then logging running for the same request and trying to
+        // report the same outcome again: it must be a no-op, not a second
+        // count against the backend's failure counter.
+        report_outcome(&mut ctx, false);
+        assert!(ctx.outcome_reported);
+    }
+}
@@ -1,11 +1,18 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use dashmap::DashMap;
 use http::Uri;
 use log::info;
 use once_cell::sync::Lazy;
 use pingora::services::background::background_service;
-use pingora_core::{services::Service, upstreams::peer::HttpPeer};
+use pingora_core::{
+    protocols::ALPN,
+    services::Service,
+    upstreams::peer::{HttpPeer, TcpKeepalive},
+};
 use pingora_error::{Error, Result};
 use pingora_http::{RequestHeader, ResponseHeader};
 use pingora_load_balancing::{
@@ -16,8 +23,6 @@ use pingora_load_balancing::{
     Backend, Backends, LoadBalancer,
 };
 use pingora_proxy::Session;
-use pingora_runtime::Runtime;
-use tokio::sync::watch;
 
 use crate::{
     config::{self, Identifiable},
@@ -44,8 +49,38 @@ pub fn upstream_fetch(id: &str) -> Option<Arc<ProxyUpstream>> {
 pub struct ProxyUpstream {
     pub inner: config::Upstream,
     lb: SelectionLB,
-    runtime: Option<Runtime>,
-    watch: Option<watch::Sender<bool>>,
+    /// Passive (in-band) health state per backend, keyed by backend address.
+    /// Complements the active checks run by `lb`'s background service.
+    passive_health: DashMap<String, PassiveBackendState>,
+}
+
+/// Tracks passive health state for a single backend, derived from the
+/// outcome of real proxied requests rather than active probes.
+struct PassiveBackendState {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl PassiveBackendState {
+    fn new() -> Self {
+        Self {
+            consecutive_failures: 0,
+            ejected_until: None,
+        }
+    }
+
+    /// Records a single failure, ejecting the backend once `threshold`
+    /// consecutive failures are reached, for `recovery`.
+    fn record_failure(&mut self, threshold: u32, recovery: Duration) {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures >= threshold {
+            self.ejected_until = Some(Instant::now() + recovery);
+        }
+    }
+
+    fn is_ejected(&self) -> bool {
+        self.ejected_until.is_some_and(|until| Instant::now() < until)
+    }
 }
 
 impl Identifiable for ProxyUpstream {
@@ -58,74 +93,40 @@ impl Identifiable for ProxyUpstream {
     }
 }
 
-// ! Each ProxyUpstream with health check will still create its own pingora_runtime::Runtime.
-// ! This will result in a potentially large number of threads being created (number of threads = number of upstreams * number of threads per Runtime).
-// ! It is strongly recommended to use the Background Service mechanism provided by Pingora Server to run health checks instead.
 impl ProxyUpstream {
-    pub fn new_with_health_check(upstream: config::Upstream, work_stealing: bool) -> Result<Self> {
-        let mut proxy_upstream = ProxyUpstream {
+    /// Builds a `ProxyUpstream` from the given configuration.
+    ///
+    /// The health check, if configured, is built as a Pingora `BackgroundService`
+    /// (see `LB::try_from`) but is not started here. Callers must take it via
+    /// `take_background_service` and register it with the `Server`'s shared
+    /// runtime, e.g. through `load_static_upstreams`.
+    pub fn new(upstream: config::Upstream) -> Result<Self> {
+        Ok(ProxyUpstream {
             inner: upstream.clone(),
-            lb: SelectionLB::try_from(upstream.clone())?,
-            runtime: None,
-            watch: None,
-        };
-        proxy_upstream.start_health_check(work_stealing);
-        Ok(proxy_upstream)
-    }
-
-    /// Starts the health check service, runs only once.
-    fn start_health_check(&mut self, work_stealing: bool) {
-        if let Some(mut service) = self.take_background_service() {
-            // Create a channel for watching the health check status
-            let (watch_tx, watch_rx) = watch::channel(false);
-            self.watch = Some(watch_tx);
-
-            // Determine the number of threads for the service
-            let threads = service.threads().unwrap_or(1);
-
-            // Create a runtime based on the work_stealing flag
-            let runtime = self.create_runtime(work_stealing, threads, service.name());
-
-            // Spawn the service on the runtime
-            runtime.get_handle().spawn(async move {
-                service
-                    .start_service(
-                        #[cfg(unix)]
-                        None,
-                        watch_rx,
-                        1,
-                    )
-                    .await;
-                info!("Service exited.");
-            });
-            // Set the runtime lifecycle with ProxyUpstream
-            self.runtime = Some(runtime);
-        }
-    }
-
-    fn create_runtime(&self, work_stealing: bool, threads: usize, service_name: &str) -> Runtime {
-        if work_stealing {
-            Runtime::new_steal(threads, service_name)
-        } else {
-            Runtime::new_no_steal(threads, service_name)
-        }
+            lb: SelectionLB::try_from(upstream)?,
+            passive_health: DashMap::new(),
+        })
     }
 
     /// Selects a backend server for a given session.
+    ///
+    /// Backends ejected by passive health checking (see `report_result`) are
+    /// skipped until their recovery interval elapses.
     pub fn select_backend<'a>(&'a self, session: &'a mut Session) -> Option<Backend> {
         let key = request_selector_key(session, &self.inner.hash_on, self.inner.key.as_str());
         log::debug!("proxy lb key: {}", &key);
 
+        let is_eligible = |b: &Backend, healthy: bool| healthy && !self.is_passively_ejected(b);
         let mut backend = match &self.lb {
-            SelectionLB::RoundRobin(lb) => lb.upstreams.select(key.as_bytes(), 256),
-            SelectionLB::Random(lb) => lb.upstreams.select(key.as_bytes(), 256),
-            SelectionLB::Fnv(lb) => lb.upstreams.select(key.as_bytes(), 256),
-            SelectionLB::Ketama(lb) => lb.upstreams.select(key.as_bytes(), 256),
+            SelectionLB::RoundRobin(lb) => lb.upstreams.select_with(key.as_bytes(), 256, is_eligible),
+            SelectionLB::Random(lb) => lb.upstreams.select_with(key.as_bytes(), 256, is_eligible),
+            SelectionLB::Fnv(lb) => lb.upstreams.select_with(key.as_bytes(), 256, is_eligible),
+            SelectionLB::Ketama(lb) => lb.upstreams.select_with(key.as_bytes(), 256, is_eligible),
         };
 
         if let Some(backend) = backend.as_mut() {
             if let Some(peer) = backend.ext.get_mut::<HttpPeer>() {
-                self.set_timeout(peer);
+                self.apply_peer_options(peer);
             }
         }
 
@@ -151,15 +152,12 @@ impl ProxyUpstream {
         }
     }
 
-    /// Stops the health check service.
-    fn stop_health_check(&mut self) {
-        if let Some(tx) = self.watch.take() {
-            let _ = tx.send(true);
-        }
-    }
-
-    /// Takes the background service if it exists.
-    fn take_background_service(&mut self) -> Option<Box<dyn Service + 'static>> {
+    /// Takes the background health check service if it exists.
+    ///
+    /// The returned service should be registered with the Pingora `Server`
+    /// (via `server.add_service(..)`) so health checks run on the server's
+    /// shared runtime instead of a dedicated one per upstream.
+    pub fn take_background_service(&mut self) -> Option<Box<dyn Service + 'static>> {
         match self.lb {
             SelectionLB::RoundRobin(ref mut lb) => lb.service.take(),
             SelectionLB::Random(ref mut lb) => lb.service.take(),
@@ -168,6 +166,75 @@ impl ProxyUpstream {
         }
     }
 
+    /// Returns the number of backends currently passing their health check,
+    /// out of the total number of backends configured for this upstream.
+    ///
+    /// Used by the readiness probe to report per-upstream serving health.
+    ///
+    /// A backend only counts as healthy if it both passes its active check
+    /// and isn't currently ejected by passive health checking (see
+    /// `report_result`) — otherwise `/ready` could report a backend as
+    /// healthy that `select_backend` would never actually route to.
+    pub fn healthy_backend_count(&self) -> (usize, usize) {
+        let is_routable = |b: &Backend| !self.is_passively_ejected(b);
+        match &self.lb {
+            SelectionLB::RoundRobin(lb) => count_healthy_backends(&lb.upstreams, is_routable),
+            SelectionLB::Random(lb) => count_healthy_backends(&lb.upstreams, is_routable),
+            SelectionLB::Fnv(lb) => count_healthy_backends(&lb.upstreams, is_routable),
+            SelectionLB::Ketama(lb) => count_healthy_backends(&lb.upstreams, is_routable),
+        }
+    }
+
+    /// Reports the outcome of a proxied request against `backend`, feeding
+    /// passive (in-band) health checking.
+    ///
+    /// Once `unhealthy`'s consecutive-failure threshold is reached the
+    /// backend is ejected from `select_backend` until the `healthy`
+    /// recovery interval elapses, giving fast failover for backends that
+    /// accept connections but return errors, without waiting on the next
+    /// active-probe interval.
+    pub fn report_result(&self, backend: &Backend, success: bool) {
+        let key = backend.addr.to_string();
+
+        if success {
+            self.passive_health.remove(&key);
+            return;
+        }
+
+        let Some(checks) = self.inner.checks.as_ref() else {
+            return;
+        };
+        let threshold = checks
+            .active
+            .unhealthy
+            .as_ref()
+            .map(|u| match checks.active.r#type {
+                config::ActiveCheckType::TCP => u.tcp_failures,
+                config::ActiveCheckType::HTTP | config::ActiveCheckType::HTTPS => u.http_failures,
+            })
+            .unwrap_or(u32::MAX);
+        let recovery = checks
+            .active
+            .healthy
+            .as_ref()
+            .map(|h| Duration::from_secs(h.interval as _))
+            .unwrap_or(Duration::from_secs(1));
+
+        let mut state = self
+            .passive_health
+            .entry(key)
+            .or_insert_with(PassiveBackendState::new);
+        state.record_failure(threshold, recovery);
+    }
+
+    /// Whether `backend` is currently ejected by passive health checking.
+    fn is_passively_ejected(&self, backend: &Backend) -> bool {
+        let key = backend.addr.to_string();
+        self.passive_health
+            .get(&key)
+            .is_some_and(|state| state.is_ejected())
+    }
+
     /// Gets the number of retries from the upstream configuration.
     pub fn get_retries(&self) -> Option<usize> {
         self.inner.retries.map(|r| r as _)
@@ -178,8 +245,10 @@ impl ProxyUpstream {
         self.inner.retry_timeout
     }
 
-    /// Sets the timeout for an `HttpPeer`.
-    fn set_timeout(&self, p: &mut HttpPeer) {
+    /// Applies the upstream's configured transport options to an
+    /// `HttpPeer`: timeouts, ALPN/h2c negotiation, and the TCP-level tuning
+    /// below (TCP Fast Open, keepalive).
+    fn apply_peer_options(&self, p: &mut HttpPeer) {
         if let Some(config::Timeout {
             connect,
             read,
@@ -190,29 +259,69 @@ impl ProxyUpstream {
             p.options.read_timeout = Some(Duration::from_secs(read));
             p.options.write_timeout = Some(Duration::from_secs(send));
         }
-    }
-}
 
-impl Drop for ProxyUpstream {
-    /// Stops the health check service if it exists.
-    fn drop(&mut self) {
-        self.stop_health_check();
+        self.set_alpn(p);
+        self.set_tcp_options(p);
+    }
 
-        // Ensure other resources like runtime are released
-        if let Some(runtime) = self.runtime.take() {
-            // Get the runtime handle
-            let handler = runtime.get_handle().clone();
+    /// Configures TCP Fast Open and server-side TCP keepalive on an
+    /// `HttpPeer`, letting operators reduce connection setup latency and
+    /// detect half-open upstream sockets.
+    fn set_tcp_options(&self, p: &mut HttpPeer) {
+        if self.inner.tcp_fast_open {
+            p.options.tcp_fast_open = true;
+        }
 
-            // Use handler to execute shutdown logic
-            handler.spawn_blocking(move || {
-                runtime.shutdown_timeout(Duration::from_secs(1));
+        if let Some(config::TcpKeepalive {
+            idle,
+            interval,
+            count,
+        }) = self.inner.tcp_keepalive
+        {
+            p.options.tcp_keepalive = Some(TcpKeepalive {
+                idle: Duration::from_secs(idle),
+                interval: Duration::from_secs(interval),
+                count: count as usize,
             });
+        }
+    }
 
-            info!("Runtime shutdown successfully.");
+    /// Sets the ALPN protocol hint for an `HttpPeer` based on
+    /// `upstream.scheme`: `http1` forces HTTP/1.1 and `http2`/`h2c` both
+    /// negotiate HTTP/2.
+    ///
+    /// Whether the connection is TLS or cleartext is *not* decided here —
+    /// it's fixed by the `tls` flag the `HttpPeer` was constructed with in
+    /// `HybridDiscovery` (from the upstream node's own scheme), and can't be
+    /// changed retroactively via `options` on the peer we get back from
+    /// `backend.ext`. For `h2c` to actually speak cleartext HTTP/2, the
+    /// upstream's nodes must be configured as plain `http` so discovery
+    /// builds the peer with `tls = false`; `ALPN::H2` here then hints the
+    /// connector to use HTTP/2 over that cleartext connection.
+    fn set_alpn(&self, p: &mut HttpPeer) {
+        match self.inner.scheme {
+            config::UpstreamScheme::HTTP1 => p.options.alpn = ALPN::H1,
+            config::UpstreamScheme::HTTP2 | config::UpstreamScheme::H2C => {
+                p.options.alpn = ALPN::H2;
+            }
         }
     }
 }
 
+/// Counts how many of a load balancer's backends are currently passing
+/// their active health check and accepted by `is_routable`.
+fn count_healthy_backends<BS: BackendSelection>(
+    lb: &LoadBalancer<BS>,
+    is_routable: impl Fn(&Backend) -> bool,
+) -> (usize, usize) {
+    let backends = lb.backends().get_backend();
+    let healthy = backends
+        .iter()
+        .filter(|b| lb.backends().ready(b) && is_routable(b))
+        .count();
+    (healthy, backends.len())
+}
+
 enum SelectionLB {
     RoundRobin(LB<RoundRobin>),
     Random(LB<Random>),
@@ -387,7 +496,15 @@ impl From<config::HealthCheck> for Box<HttpHealthCheck> {
 pub static UPSTREAM_MAP: Lazy<DashMap<String, Arc<ProxyUpstream>>> = Lazy::new(DashMap::new);
 
 /// Loads upstreams from the given configuration.
-pub fn load_static_upstreams(config: &config::Config) -> Result<()> {
+///
+/// Returns the health check background services collected from every
+/// configured upstream so the caller can register them with the Pingora
+/// `Server` (e.g. `server.add_service(service)`), letting health checks run
+/// on the server's shared runtime rather than spawning one runtime per
+/// upstream.
+pub fn load_static_upstreams(config: &config::Config) -> Result<Vec<Box<dyn Service>>> {
+    let mut health_check_services: Vec<Box<dyn Service>> = Vec::new();
+
     // Collect all ProxyUpstream instances into a vector.
     let proxy_upstreams: Vec<Arc<ProxyUpstream>> = config
         .upstreams
@@ -406,11 +523,13 @@ pub fn load_static_upstreams(config: &config::Config) -> Result<()> {
                 );
                 std::process::exit(1);
             }
-            match ProxyUpstream::new_with_health_check(
-                upstream.clone(),
-                config.pingora.work_stealing,
-            ) {
-                Ok(proxy_upstream) => Ok(Arc::new(proxy_upstream)),
+            match ProxyUpstream::new(upstream.clone()) {
+                Ok(mut proxy_upstream) => {
+                    if let Some(service) = proxy_upstream.take_background_service() {
+                        health_check_services.push(service);
+                    }
+                    Ok(Arc::new(proxy_upstream))
+                }
                 Err(e) => {
                     log::error!("Failed to configure Upstream {}: {}", upstream.id, e);
                     Err(e)
@@ -422,5 +541,42 @@ pub fn load_static_upstreams(config: &config::Config) -> Result<()> {
     // Insert all ProxyUpstream instances into the global map.
     UPSTREAM_MAP.reload_resources(proxy_upstreams);
 
-    Ok(())
+    Ok(health_check_services)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_failure_ejects_after_threshold() {
+        let mut state = PassiveBackendState::new();
+
+        state.record_failure(3, Duration::from_secs(60));
+        assert!(!state.is_ejected(), "should not eject before the threshold");
+
+        state.record_failure(3, Duration::from_secs(60));
+        assert!(!state.is_ejected());
+
+        state.record_failure(3, Duration::from_secs(60));
+        assert!(state.is_ejected(), "should eject once the threshold is reached");
+    }
+
+    #[test]
+    fn record_failure_recovers_after_interval() {
+        let mut state = PassiveBackendState::new();
+        state.record_failure(1, Duration::from_millis(20));
+        assert!(state.is_ejected());
+
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(
+            !state.is_ejected(),
+            "should become routable again once the recovery interval elapses"
+        );
+    }
+
+    #[test]
+    fn fresh_state_is_not_ejected() {
+        assert!(!PassiveBackendState::new().is_ejected());
+    }
 }